@@ -18,6 +18,40 @@ pub fn cmd_app() -> App<'static, 'static> {
 				.takes_value(false),
 		)
 		.arg(Arg::with_name("no_audit").long("no-audit").help("Do not do a npm audit").takes_value(false))
+		.arg(
+			Arg::with_name("fail_on")
+				.long("fail-on")
+				.value_name("SEVERITY")
+				.possible_values(&["info", "low", "moderate", "high", "critical"])
+				.help("Exit with a nonzero code when a vulnerability at or above this severity is found")
+				.takes_value(true),
+		)
+		.arg(
+			Arg::with_name("diff")
+				.long("diff")
+				.help("Diff the two most recent runs stored in .audit/audit.db (this run and the one before it, unless --no-audit is given)")
+				.takes_value(false),
+		)
+		.arg(
+			Arg::with_name("fail_on_audit_error")
+				.long("fail-on-audit-error")
+				.help("Exit with a nonzero code when a package's audit invocation itself fails (malformed JSON, registry auth failure, etc.), in addition to the --fail-on severity gate")
+				.takes_value(false),
+		)
+		.arg(
+			Arg::with_name("list")
+				.long("list")
+				.help("Dry-run: list the discovered package directories and the planned bundle contents")
+				.takes_value(false),
+		)
+		.arg(
+			Arg::with_name("pm")
+				.long("pm")
+				.value_name("PM")
+				.possible_values(&["npm", "yarn", "pnpm"])
+				.default_value("npm")
+				.help("Package manager to use for directories without an existing lockfile"),
+		)
 		.arg(Arg::with_name("PATH").help("Path to root"));
 
 	app
@@ -0,0 +1,500 @@
+use crate::pm::PackageManager;
+use crate::MainError;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+
+// region:    severity
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+	Info,
+	Low,
+	Moderate,
+	High,
+	Critical,
+}
+
+impl Severity {
+	pub fn as_str(&self) -> &'static str {
+		match self {
+			Severity::Info => "info",
+			Severity::Low => "low",
+			Severity::Moderate => "moderate",
+			Severity::High => "high",
+			Severity::Critical => "critical",
+		}
+	}
+}
+
+impl FromStr for Severity {
+	type Err = MainError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"info" => Ok(Severity::Info),
+			"low" => Ok(Severity::Low),
+			"moderate" => Ok(Severity::Moderate),
+			"high" => Ok(Severity::High),
+			"critical" => Ok(Severity::Critical),
+			_ => Err(MainError::InvalidArgument(format!("Unknown severity '{}'", s))),
+		}
+	}
+}
+// endregion: severity
+
+// region:    model
+/// A single vulnerability found in a package directory (from `npm audit --json`)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Advisory {
+	pub id: String,
+	pub module_name: String,
+	pub severity: Severity,
+	pub vulnerable_range: String,
+	pub fix_available: bool,
+	pub via: Vec<String>,
+}
+
+/// All advisories found for one package.json directory
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PackageAudit {
+	pub package_name: String,
+	pub advisories: Vec<Advisory>,
+}
+
+/// Rolled-up counts across every package directory
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct AuditSummary {
+	pub info: u32,
+	pub low: u32,
+	pub moderate: u32,
+	pub high: u32,
+	pub critical: u32,
+	pub affected_packages: u32,
+}
+
+impl AuditSummary {
+	pub fn from_package_audits(audits: &[PackageAudit]) -> AuditSummary {
+		let mut summary = AuditSummary::default();
+
+		for pkg in audits {
+			if !pkg.advisories.is_empty() {
+				summary.affected_packages += 1;
+			}
+			for advisory in pkg.advisories.iter() {
+				match advisory.severity {
+					Severity::Info => summary.info += 1,
+					Severity::Low => summary.low += 1,
+					Severity::Moderate => summary.moderate += 1,
+					Severity::High => summary.high += 1,
+					Severity::Critical => summary.critical += 1,
+				}
+			}
+		}
+
+		summary
+	}
+
+	pub fn to_text(&self) -> String {
+		format!(
+			"info: {}\nlow: {}\nmoderate: {}\nhigh: {}\ncritical: {}\n\naffected packages: {}\n",
+			self.info, self.low, self.moderate, self.high, self.critical, self.affected_packages
+		)
+	}
+}
+// endregion: model
+
+// region:    npm audit
+/// Runs `<pm> audit --json` in `dir` and returns the raw stdout.
+///
+/// When `offline` is set (a lockfile was found and install was skipped), the manager's
+/// lockfile-only equivalent is used so the audit does not need `node_modules`: npm gets
+/// `--package-lock-only`; yarn and pnpm already audit straight off their lockfile. The audit
+/// itself still queries the registry's advisory data.
+///
+/// Note: `audit` exits with a nonzero status when vulnerabilities are found,
+/// so we intentionally ignore the process exit status here and only look at stdout.
+pub fn run_audit_json(dir: &Path, pm: PackageManager, offline: bool) -> Result<String, MainError> {
+	let mut cmd = Command::new(pm.binary());
+	cmd.current_dir(dir).arg("audit").arg("--json");
+
+	if offline && pm == PackageManager::Npm {
+		cmd.arg("--package-lock-only");
+	}
+
+	let output = cmd.output().map_err(|source| MainError::ReadError { source })?;
+	Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Parses the raw `<pm> audit --json` stdout into our typed model. `npm` (v7+) uses a single
+/// JSON object with a `vulnerabilities` map keyed by module name; `pnpm` instead mirrors the
+/// legacy npm v6 shape (a top-level `advisories` object keyed by numeric advisory id, with
+/// counts under `metadata.vulnerabilities`); classic `yarn` (v1) streams one JSON object per
+/// line. Each manager therefore gets its own parser.
+///
+/// Returns an error rather than an empty `Vec` when the output isn't a well-formed audit
+/// report, so a broken/offline invocation can't be mistaken for "audited, zero findings".
+pub fn parse_package_audit_for(pm: PackageManager, package_name: &str, raw: &str) -> Result<Vec<Advisory>, MainError> {
+	match pm {
+		PackageManager::Yarn => parse_yarn_audit(raw),
+		PackageManager::Pnpm => parse_pnpm_audit(package_name, raw),
+		PackageManager::Npm => {
+			let json: Value = serde_json::from_str(raw).map_err(|_| {
+				MainError::InvalidArgument(format!("'{}' {} audit output was not valid JSON", package_name, pm.binary()))
+			})?;
+
+			if let Some(error) = json.get("error") {
+				return Err(MainError::InvalidArgument(format!(
+					"{} audit failed for '{}': {}",
+					pm.binary(),
+					package_name,
+					error
+				)));
+			}
+
+			if json.get("vulnerabilities").is_none() {
+				return Err(MainError::InvalidArgument(format!(
+					"'{}' {} audit output is missing the 'vulnerabilities' field",
+					package_name,
+					pm.binary()
+				)));
+			}
+
+			Ok(parse_package_audit(package_name, &json))
+		}
+	}
+}
+
+/// Parses the newline-delimited `auditAdvisory` records emitted by `yarn audit --json`.
+///
+/// Errors when `raw` has no output at all, none of its lines parse as JSON, or no
+/// `auditSummary` record is present - each indicates the audit never actually completed
+/// rather than "ran and found nothing".
+fn parse_yarn_audit(raw: &str) -> Result<Vec<Advisory>, MainError> {
+	if raw.trim().is_empty() {
+		return Err(MainError::InvalidArgument("yarn audit produced no output".to_owned()));
+	}
+
+	let mut advisories = Vec::new();
+	let mut parsed_any_line = false;
+	let mut saw_summary = false;
+
+	for line in raw.lines() {
+		let record: Value = match serde_json::from_str(line) {
+			Ok(record) => record,
+			Err(_) => continue,
+		};
+		parsed_any_line = true;
+		if record.get("type").and_then(Value::as_str) == Some("auditSummary") {
+			saw_summary = true;
+		}
+		if record.get("type").and_then(Value::as_str) != Some("auditAdvisory") {
+			continue;
+		}
+		let data = match record.get("data").and_then(|d| d.get("advisory")) {
+			Some(data) => data,
+			None => continue,
+		};
+
+		advisories.push(advisory_from_legacy_json(data));
+	}
+
+	if !parsed_any_line {
+		return Err(MainError::InvalidArgument("yarn audit output could not be parsed as JSON".to_owned()));
+	}
+
+	if !saw_summary {
+		return Err(MainError::InvalidArgument("yarn audit output has no auditSummary record".to_owned()));
+	}
+
+	Ok(advisories)
+}
+
+/// Parses `pnpm audit --json`, which (unlike npm v7+) mirrors the legacy npm v6 shape: a
+/// top-level `advisories` object keyed by numeric advisory id, with rolled-up counts under
+/// `metadata.vulnerabilities` rather than a `vulnerabilities` map keyed by module name.
+fn parse_pnpm_audit(package_name: &str, raw: &str) -> Result<Vec<Advisory>, MainError> {
+	let json: Value = serde_json::from_str(raw)
+		.map_err(|_| MainError::InvalidArgument(format!("'{}' pnpm audit output was not valid JSON", package_name)))?;
+
+	if let Some(error) = json.get("error") {
+		return Err(MainError::InvalidArgument(format!("pnpm audit failed for '{}': {}", package_name, error)));
+	}
+
+	let advisories = match json.get("advisories").and_then(Value::as_object) {
+		Some(advisories) => advisories,
+		None => {
+			return Err(MainError::InvalidArgument(format!(
+				"'{}' pnpm audit output is missing the 'advisories' field",
+				package_name
+			)))
+		}
+	};
+
+	Ok(advisories.values().map(advisory_from_legacy_json).collect())
+}
+
+/// Builds an `Advisory` from the legacy npm v6 / yarn v1 advisory object shape shared by
+/// `yarn audit --json` (one per ndjson line) and `pnpm audit --json` (one per `advisories` entry).
+fn advisory_from_legacy_json(data: &Value) -> Advisory {
+	let module_name = data.get("module_name").and_then(Value::as_str).unwrap_or("").to_owned();
+	let severity = data
+		.get("severity")
+		.and_then(Value::as_str)
+		.and_then(|s| s.parse::<Severity>().ok())
+		.unwrap_or(Severity::Info);
+	let vulnerable_range = data.get("vulnerable_versions").and_then(Value::as_str).unwrap_or("").to_owned();
+	let fix_available = data.get("patched_versions").and_then(Value::as_str).map(|s| s != "<0.0.0").unwrap_or(false);
+	let id = data.get("id").map(|id| id.to_string()).unwrap_or_else(|| module_name.clone());
+	let via = data
+		.get("findings")
+		.and_then(Value::as_array)
+		.map(|findings| {
+			findings
+				.iter()
+				.filter_map(|finding| finding.get("paths").and_then(Value::as_array))
+				.flatten()
+				.filter_map(|path| path.as_str().map(|s| s.to_owned()))
+				.collect()
+		})
+		.unwrap_or_default();
+
+	Advisory {
+		id,
+		module_name,
+		severity,
+		vulnerable_range,
+		fix_available,
+		via,
+	}
+}
+
+/// Parses the `vulnerabilities` map of a `npm audit --json` payload into our typed model.
+pub fn parse_package_audit(package_name: &str, json: &Value) -> Vec<Advisory> {
+	let mut advisories = Vec::new();
+
+	let vulnerabilities = match json.get("vulnerabilities").and_then(Value::as_object) {
+		Some(vulnerabilities) => vulnerabilities,
+		None => return advisories,
+	};
+
+	for (module_name, v) in vulnerabilities.iter() {
+		let severity = v
+			.get("severity")
+			.and_then(Value::as_str)
+			.and_then(|s| s.parse::<Severity>().ok())
+			.unwrap_or(Severity::Info);
+
+		let vulnerable_range = v.get("range").and_then(Value::as_str).unwrap_or("").to_owned();
+
+		let fix_available = match v.get("fixAvailable") {
+			Some(Value::Bool(b)) => *b,
+			Some(Value::Object(_)) => true,
+			_ => false,
+		};
+
+		let via: Vec<String> = v
+			.get("via")
+			.and_then(Value::as_array)
+			.map(|items| {
+				items
+					.iter()
+					.filter_map(|item| match item {
+						Value::String(name) => Some(name.to_owned()),
+						Value::Object(obj) => obj.get("name").and_then(Value::as_str).map(|s| s.to_owned()),
+						_ => None,
+					})
+					.collect()
+			})
+			.unwrap_or_default();
+
+		let id = v
+			.get("via")
+			.and_then(Value::as_array)
+			.and_then(|items| items.iter().find_map(|item| item.get("source")))
+			.map(|source| source.to_string())
+			.unwrap_or_else(|| module_name.to_owned());
+
+		advisories.push(Advisory {
+			id,
+			module_name: module_name.to_owned(),
+			severity,
+			vulnerable_range,
+			fix_available,
+			via,
+		});
+	}
+
+	advisories
+}
+// endregion: npm audit
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn severity_from_str_parses_all_known_values() {
+		assert_eq!("info".parse::<Severity>().unwrap(), Severity::Info);
+		assert_eq!("low".parse::<Severity>().unwrap(), Severity::Low);
+		assert_eq!("moderate".parse::<Severity>().unwrap(), Severity::Moderate);
+		assert_eq!("high".parse::<Severity>().unwrap(), Severity::High);
+		assert_eq!("critical".parse::<Severity>().unwrap(), Severity::Critical);
+		assert_eq!("CRITICAL".parse::<Severity>().unwrap(), Severity::Critical);
+	}
+
+	#[test]
+	fn severity_from_str_errors_on_unknown_value() {
+		assert!("severe".parse::<Severity>().is_err());
+	}
+
+	#[test]
+	fn severity_ord_ranks_critical_above_info() {
+		assert!(Severity::Critical > Severity::Info);
+		assert!(Severity::Info < Severity::Low);
+		assert!(Severity::Low < Severity::Moderate);
+		assert!(Severity::Moderate < Severity::High);
+		assert!(Severity::High < Severity::Critical);
+		assert_eq!(Severity::High.max(Severity::Moderate), Severity::High);
+	}
+
+	#[test]
+	fn parse_package_audit_reads_npm_v7_shape() {
+		let json: Value = serde_json::from_str(
+			r#"{
+				"vulnerabilities": {
+					"lodash": {
+						"severity": "high",
+						"range": "<4.17.21",
+						"fixAvailable": true,
+						"via": [{ "source": 1067, "name": "lodash" }, "express"]
+					}
+				}
+			}"#,
+		)
+		.unwrap();
+
+		let advisories = parse_package_audit("pkg-a", &json);
+
+		assert_eq!(advisories.len(), 1);
+		let advisory = &advisories[0];
+		assert_eq!(advisory.id, "1067");
+		assert_eq!(advisory.module_name, "lodash");
+		assert_eq!(advisory.severity, Severity::High);
+		assert_eq!(advisory.vulnerable_range, "<4.17.21");
+		assert!(advisory.fix_available);
+		assert_eq!(advisory.via, vec!["lodash".to_owned(), "express".to_owned()]);
+	}
+
+	#[test]
+	fn parse_package_audit_for_npm_returns_empty_on_clean_report() {
+		let advisories = parse_package_audit_for(PackageManager::Npm, "pkg-a", r#"{"vulnerabilities": {}}"#).unwrap();
+		assert!(advisories.is_empty());
+	}
+
+	#[test]
+	fn parse_package_audit_for_npm_errors_on_malformed_json() {
+		let result = parse_package_audit_for(PackageManager::Npm, "pkg-a", "not json");
+		assert!(result.is_err());
+	}
+
+	#[test]
+	fn parse_package_audit_for_npm_errors_when_vulnerabilities_field_missing() {
+		let result = parse_package_audit_for(PackageManager::Npm, "pkg-a", r#"{"unrelated": true}"#);
+		assert!(result.is_err());
+	}
+
+	#[test]
+	fn parse_package_audit_for_npm_errors_on_audit_error_payload() {
+		let result = parse_package_audit_for(PackageManager::Npm, "pkg-a", r#"{"error": {"summary": "ENOTFOUND"}}"#);
+		assert!(result.is_err());
+	}
+
+	#[test]
+	fn parse_yarn_audit_reads_audit_advisory_records() {
+		let raw = [
+			r#"{"type":"info","data":"checking"}"#,
+			r#"{"type":"auditAdvisory","data":{"advisory":{"id":1179,"module_name":"lodash","severity":"high","vulnerable_versions":"<4.17.21","patched_versions":">=4.17.21","findings":[{"paths":["lodash"]}]}}}"#,
+			r#"{"type":"auditSummary","data":{"vulnerabilities":{"high":1}}}"#,
+		]
+		.join("\n");
+
+		let advisories = parse_package_audit_for(PackageManager::Yarn, "pkg-a", &raw).unwrap();
+
+		assert_eq!(advisories.len(), 1);
+		let advisory = &advisories[0];
+		assert_eq!(advisory.id, "1179");
+		assert_eq!(advisory.module_name, "lodash");
+		assert_eq!(advisory.severity, Severity::High);
+		assert!(advisory.fix_available);
+		assert_eq!(advisory.via, vec!["lodash".to_owned()]);
+	}
+
+	#[test]
+	fn parse_yarn_audit_returns_empty_on_clean_report() {
+		let raw = r#"{"type":"auditSummary","data":{"vulnerabilities":{}}}"#;
+		let advisories = parse_package_audit_for(PackageManager::Yarn, "pkg-a", raw).unwrap();
+		assert!(advisories.is_empty());
+	}
+
+	#[test]
+	fn parse_yarn_audit_errors_on_empty_output() {
+		let result = parse_package_audit_for(PackageManager::Yarn, "pkg-a", "");
+		assert!(result.is_err());
+	}
+
+	#[test]
+	fn parse_yarn_audit_errors_when_no_line_is_valid_json() {
+		let result = parse_package_audit_for(PackageManager::Yarn, "pkg-a", "command not found: yarn\n");
+		assert!(result.is_err());
+	}
+
+	#[test]
+	fn parse_pnpm_audit_reads_legacy_npm_v6_shape() {
+		let raw = r#"{
+			"advisories": {
+				"1067": {
+					"id": 1067,
+					"module_name": "lodash",
+					"severity": "high",
+					"vulnerable_versions": "<4.17.21",
+					"patched_versions": ">=4.17.21",
+					"findings": [{ "version": "4.17.15", "paths": ["lodash"] }]
+				}
+			},
+			"metadata": { "vulnerabilities": { "info": 0, "low": 0, "moderate": 0, "high": 1, "critical": 0 } }
+		}"#;
+
+		let advisories = parse_package_audit_for(PackageManager::Pnpm, "pkg-a", raw).unwrap();
+
+		assert_eq!(advisories.len(), 1);
+		let advisory = &advisories[0];
+		assert_eq!(advisory.id, "1067");
+		assert_eq!(advisory.module_name, "lodash");
+		assert_eq!(advisory.severity, Severity::High);
+		assert_eq!(advisory.vulnerable_range, "<4.17.21");
+		assert!(advisory.fix_available);
+		assert_eq!(advisory.via, vec!["lodash".to_owned()]);
+	}
+
+	#[test]
+	fn parse_pnpm_audit_returns_empty_on_clean_report() {
+		let raw = r#"{"advisories": {}, "metadata": {"vulnerabilities": {"info": 0}}}"#;
+		let advisories = parse_package_audit_for(PackageManager::Pnpm, "pkg-a", raw).unwrap();
+		assert!(advisories.is_empty());
+	}
+
+	#[test]
+	fn parse_pnpm_audit_errors_when_advisories_field_missing() {
+		let result = parse_package_audit_for(PackageManager::Pnpm, "pkg-a", r#"{"unrelated": true}"#);
+		assert!(result.is_err());
+	}
+
+	#[test]
+	fn parse_pnpm_audit_errors_on_malformed_json() {
+		let result = parse_package_audit_for(PackageManager::Pnpm, "pkg-a", "not json");
+		assert!(result.is_err());
+	}
+}
@@ -0,0 +1,187 @@
+use crate::audit::PackageAudit;
+use crate::MainError;
+use rusqlite::{params, Connection};
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const DB_FILE_NAME: &str = "audit.db";
+
+/// Opens (creating if needed) the `.audit/audit.db` history store.
+///
+/// Runs are keyed by an autoincrement `runs.id` rather than a unix timestamp: two audits
+/// started within the same wall-clock second (easy to hit with offline/lockfile-only audits)
+/// would otherwise collide onto the same key and get merged into a single "run".
+pub fn open(audit_root_dir: &Path) -> Result<Connection, MainError> {
+	let conn = Connection::open(audit_root_dir.join(DB_FILE_NAME))?;
+
+	conn.execute(
+		"CREATE TABLE IF NOT EXISTS runs (
+			id         INTEGER PRIMARY KEY,
+			created_at INTEGER NOT NULL
+		)",
+		[],
+	)?;
+
+	conn.execute(
+		"CREATE TABLE IF NOT EXISTS findings (
+			run_id           INTEGER NOT NULL,
+			package_dir      TEXT NOT NULL,
+			advisory_id      TEXT NOT NULL,
+			module_name      TEXT NOT NULL,
+			severity         TEXT NOT NULL,
+			vulnerable_range TEXT NOT NULL,
+			fix_available    INTEGER NOT NULL
+		)",
+		[],
+	)?;
+
+	Ok(conn)
+}
+
+/// Current unix timestamp (seconds), recorded alongside each run for diagnostics only -
+/// it is not used to identify or order runs.
+fn now_ts() -> i64 {
+	SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() as i64
+}
+
+/// Starts a new run and inserts every finding of this run into the `findings` table.
+/// Returns the new run's id.
+pub fn insert_run(conn: &Connection, package_audits: &[PackageAudit]) -> Result<i64, MainError> {
+	conn.execute("INSERT INTO runs (created_at) VALUES (?1)", params![now_ts()])?;
+	let run_id = conn.last_insert_rowid();
+
+	for pkg in package_audits {
+		for advisory in pkg.advisories.iter() {
+			conn.execute(
+				"INSERT INTO findings (run_id, package_dir, advisory_id, module_name, severity, vulnerable_range, fix_available)
+				 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+				params![
+					run_id,
+					pkg.package_name,
+					advisory.id,
+					advisory.module_name,
+					advisory.severity.as_str(),
+					advisory.vulnerable_range,
+					advisory.fix_available as i64,
+				],
+			)?;
+		}
+	}
+
+	Ok(run_id)
+}
+
+#[derive(Debug, Clone)]
+pub struct Finding {
+	pub package_dir: String,
+	pub advisory_id: String,
+	pub module_name: String,
+	pub severity: String,
+	pub vulnerable_range: String,
+	pub fix_available: bool,
+}
+
+fn finding_key(f: &Finding) -> (String, String, String) {
+	(f.package_dir.clone(), f.advisory_id.clone(), f.module_name.clone())
+}
+
+/// Returns (previous_run_id, latest_run_id) if at least two runs are recorded.
+pub fn last_two_runs(conn: &Connection) -> Result<Option<(i64, i64)>, MainError> {
+	let mut stmt = conn.prepare("SELECT id FROM runs ORDER BY id DESC LIMIT 2")?;
+	let runs: Vec<i64> = stmt.query_map([], |row| row.get(0))?.collect::<Result<_, _>>()?;
+
+	Ok(match runs.as_slice() {
+		[latest, previous] => Some((*previous, *latest)),
+		_ => None,
+	})
+}
+
+pub fn findings_for_run(conn: &Connection, run_id: i64) -> Result<Vec<Finding>, MainError> {
+	let mut stmt = conn.prepare(
+		"SELECT package_dir, advisory_id, module_name, severity, vulnerable_range, fix_available
+		 FROM findings WHERE run_id = ?1",
+	)?;
+
+	let findings = stmt
+		.query_map(params![run_id], |row| {
+			Ok(Finding {
+				package_dir: row.get(0)?,
+				advisory_id: row.get(1)?,
+				module_name: row.get(2)?,
+				severity: row.get(3)?,
+				vulnerable_range: row.get(4)?,
+				fix_available: row.get::<_, i64>(5)? != 0,
+			})
+		})?
+		.collect::<Result<Vec<_>, _>>()?;
+
+	Ok(findings)
+}
+
+pub struct RunDiff {
+	pub new_findings: Vec<Finding>,
+	pub resolved_findings: Vec<Finding>,
+	pub unchanged_findings: Vec<Finding>,
+}
+
+/// Compares the findings of two runs into newly-appeared, resolved and unchanged sets.
+pub fn diff_runs(previous: &[Finding], current: &[Finding]) -> RunDiff {
+	let previous_keys: HashSet<_> = previous.iter().map(finding_key).collect();
+	let current_keys: HashSet<_> = current.iter().map(finding_key).collect();
+
+	let new_findings = current.iter().filter(|f| !previous_keys.contains(&finding_key(f))).cloned().collect();
+	let resolved_findings = previous.iter().filter(|f| !current_keys.contains(&finding_key(f))).cloned().collect();
+	let unchanged_findings = current.iter().filter(|f| previous_keys.contains(&finding_key(f))).cloned().collect();
+
+	RunDiff {
+		new_findings,
+		resolved_findings,
+		unchanged_findings,
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn finding(package_dir: &str, advisory_id: &str) -> Finding {
+		Finding {
+			package_dir: package_dir.to_owned(),
+			advisory_id: advisory_id.to_owned(),
+			module_name: "lodash".to_owned(),
+			severity: "high".to_owned(),
+			vulnerable_range: "<4.17.21".to_owned(),
+			fix_available: true,
+		}
+	}
+
+	#[test]
+	fn diff_runs_splits_new_resolved_and_unchanged() {
+		let previous = vec![finding("pkg-a", "1067"), finding("pkg-a", "1179")];
+		let current = vec![finding("pkg-a", "1067"), finding("pkg-a", "9999")];
+
+		let diff = diff_runs(&previous, &current);
+
+		assert_eq!(diff.new_findings.len(), 1);
+		assert_eq!(diff.new_findings[0].advisory_id, "9999");
+
+		assert_eq!(diff.resolved_findings.len(), 1);
+		assert_eq!(diff.resolved_findings[0].advisory_id, "1179");
+
+		assert_eq!(diff.unchanged_findings.len(), 1);
+		assert_eq!(diff.unchanged_findings[0].advisory_id, "1067");
+	}
+
+	#[test]
+	fn diff_runs_treats_same_advisory_in_different_packages_as_distinct() {
+		let previous = vec![finding("pkg-a", "1067")];
+		let current = vec![finding("pkg-b", "1067")];
+
+		let diff = diff_runs(&previous, &current);
+
+		assert_eq!(diff.new_findings.len(), 1);
+		assert_eq!(diff.resolved_findings.len(), 1);
+		assert!(diff.unchanged_findings.is_empty());
+	}
+}
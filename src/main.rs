@@ -1,22 +1,29 @@
 // #![allow(unused)] // silence unused warnings
 mod argv;
+mod audit;
+mod db;
+mod pm;
 
 use argv::cmd_app;
 use fs::canonicalize;
 use globwalk::GlobError;
 use io::{BufReader, Write};
 use libflate::gzip::Encoder;
+use pm::PackageManager;
+use rusqlite::Connection;
 use serde_json::Value;
 use std::{
 	fs::{self, copy, create_dir_all, remove_dir_all, remove_file, write, File},
 	io,
 	path::{Path, PathBuf},
-	process::{Command, Stdio},
+	process::{self, Command},
 };
 use tar::Builder;
 use thiserror::Error;
 
 const AUDIT_ROOT_DIR_NAME: &str = ".audit";
+/// Fixed modification time (unix epoch) used for every tar entry so the archive is reproducible.
+const TAR_EPOCH: u64 = 0;
 
 #[derive(Error, Debug)]
 pub enum MainError {
@@ -34,18 +41,39 @@ pub enum MainError {
 	#[error("Path Error")]
 	PathNotExist(String),
 
+	/// An argument value (e.g. `--fail-on`, `--pm`) or a package manager's audit output
+	/// could not be interpreted.
+	#[error("Invalid input: {0}")]
+	InvalidArgument(String),
+
 	#[error(transparent)]
 	SerdeError(#[from] serde_json::Error),
 
 	#[error(transparent)]
 	GlobError(#[from] GlobError),
 
+	#[error(transparent)]
+	SqliteError(#[from] rusqlite::Error),
+
 	/// Represents all other cases of `std::io::Error`.
 	#[error(transparent)]
 	IOError(#[from] std::io::Error),
 }
 
 fn main() {
+	match run() {
+		Ok(exit_code) => process::exit(exit_code),
+		Err(ex) => crash(&ex.to_string(), 1),
+	}
+}
+
+/// Prints `msg` as an error and terminates the process with `code`.
+fn crash(msg: &str, code: i32) -> ! {
+	eprintln!("ERROR - {}", msg);
+	process::exit(code);
+}
+
+fn run() -> Result<i32, MainError> {
 	// parse the arguments
 	let cmd = cmd_app().get_matches();
 
@@ -55,18 +83,20 @@ fn main() {
 	let package_path = root.to_path_buf().join("package.json");
 	let package_path = package_path.as_path();
 	if !package_path.is_file() {
-		println!(
-			"ERROR - Path '{}' does not contain a package.json - abort",
-			package_path.to_str().unwrap()
+		crash(
+			&format!("Path '{}' does not contain a package.json - abort", package_path.display()),
+			2,
 		);
-		return;
 	}
 
-	let drop_name = get_drop_name(&root).unwrap();
+	let drop_name = get_drop_name(&root)?;
 
 	let do_clean = cmd.is_present("clean");
 	let do_install = !cmd.is_present("no_install");
 	let do_audit = !cmd.is_present("no_audit");
+	let fail_on: Option<audit::Severity> = cmd.value_of("fail_on").map(str::parse::<audit::Severity>).transpose()?;
+	let fail_on_audit_error = cmd.is_present("fail_on_audit_error");
+	let selected_pm: PackageManager = cmd.value_of("pm").unwrap_or("npm").parse()?;
 
 	// init the audit dir
 	let audit_root_dir = PathBuf::from(root).join(AUDIT_ROOT_DIR_NAME);
@@ -75,55 +105,149 @@ fn main() {
 	let audit_dir = PathBuf::from(audit_root_dir).join(&audit_name);
 	let audit_dir = audit_dir.as_path();
 
+	// list of package.json directories
+	let dirs = list_package_dirs(root)?;
+
+	// --list dry-run: report what would be touched, without installing, auditing, or deleting anything
+	if cmd.is_present("list") {
+		println!("=== package directories ({})", dirs.len());
+		for (dir, name, detected_pm) in dirs.iter() {
+			match detected_pm {
+				Some(detected_pm) => println!("  {} - {} ({} lockfile found)", name, dir.display(), detected_pm.binary()),
+				None => println!("  {} - {}", name, dir.display()),
+			}
+		}
+
+		if audit_dir.is_dir() {
+			println!("=== planned bundle contents ({})", audit_name);
+			for (file, name) in list_audit_files(audit_dir)?.iter() {
+				let size = fs::metadata(file)?.len();
+				println!("  {} ({})", name, human_size(size));
+			}
+		} else {
+			println!("=== no existing audit dir - nothing to bundle yet");
+		}
+
+		return Ok(0);
+	}
+
 	// clean the audit dir
 	if do_audit {
-		safer_remove_dir(audit_dir).expect("Canot remove audit dir");
-		create_dir_all(audit_dir).expect("Cant create audit dir");
+		safer_remove_dir(audit_dir)?;
+		create_dir_all(audit_dir)?;
 	}
 
-	// list of package.json directories
-	let dirs = list_package_dirs(root).unwrap();
-
 	// clean packages
 	if do_clean {
-		clean_packages(root).expect("Can't clean packages");
+		clean_packages(root)?;
 	}
 
-	// npm install
+	// install (skip automatically for packages that already have a lockfile - they can be audited offline)
 	if do_install {
-		for (dir, name) in dirs.iter() {
-			println!("=== npm install {}\n", name);
-			cmd_install(dir.as_path());
+		for (dir, name, detected_pm) in dirs.iter() {
+			if let Some(detected_pm) = detected_pm {
+				println!("=== {} lockfile found, skipping install for {}\n", detected_pm.binary(), name);
+				continue;
+			}
+			println!("=== {} install {}\n", selected_pm.binary(), name);
+			cmd_install(dir.as_path(), selected_pm)?;
 		}
 	}
 
 	// audit
+	let mut exit_code = 0;
 	if do_audit {
-		let mut audit_content = String::new();
-		for (dir, name) in dirs.iter() {
-			let out = cmd_audit(dir);
-			let txt = format!("\n==== AUDIT FOR  {} ===={}\n", name, out);
-			println!("{}", txt);
-			audit_content.push_str(&txt);
+		let mut package_audits: Vec<audit::PackageAudit> = Vec::new();
+		let mut audit_failed = false;
+		for (dir, name, detected_pm) in dirs.iter() {
+			let pm = detected_pm.as_ref().copied().unwrap_or(selected_pm);
+			println!("=== {} audit {}\n", pm.binary(), name);
+			let advisories = audit::run_audit_json(dir, pm, detected_pm.is_some())
+				.and_then(|raw| audit::parse_package_audit_for(pm, name, &raw));
+			match advisories {
+				Ok(advisories) => package_audits.push(audit::PackageAudit {
+					package_name: name.clone(),
+					advisories,
+				}),
+				Err(ex) => {
+					println!("ERROR {} audit {} - {}", pm.binary(), name, ex);
+					audit_failed = true;
+				}
+			}
 		}
 
-		// write the audit file content
-		audit_content.push_str(
-			"\n\n========= NOTE:\nnpm audit --audit-level=moderate (for each node directory)\n",
-		);
+		let summary = audit::AuditSummary::from_package_audits(&package_audits);
+
+		// write the structured findings
+		let audit_json_file = audit_dir.join("_audit.json");
+		match serde_json::to_string_pretty(&package_audits) {
+			Ok(content) => match write(&audit_json_file, content) {
+				Ok(_) => println!("=== Save audit file {}", audit_json_file.to_str().unwrap()),
+				Err(ex) => println!("ERROR {}", ex),
+			},
+			Err(ex) => println!("ERROR {}", ex),
+		}
 
-		let audit_file = audit_dir.join("_audit.txt");
-		match write(&audit_file, audit_content) {
-			Ok(_) => println!("=== Save audit file {}", audit_file.to_str().unwrap()),
+		// write the rolled-up summary
+		let audit_txt_file = audit_dir.join("_audit.txt");
+		match write(&audit_txt_file, summary.to_text()) {
+			Ok(_) => println!("=== Save audit file {}", audit_txt_file.to_str().unwrap()),
 			Err(ex) => println!("ERROR {}", ex),
 		}
 
-		//// copy the package-locks
-		for (dir, name) in dirs.iter() {
-			let package_lock_file = dir.join("package-lock.json");
-			let name = format!("{}-package-lock.json", name.replace("/", "-"));
-			let dist = audit_dir.join(name);
-			copy(package_lock_file, dist).expect("Fail to copy a package-lock");
+		//// copy the lockfiles
+		for (dir, name, detected_pm) in dirs.iter() {
+			let pm = detected_pm.as_ref().copied().unwrap_or(selected_pm);
+			let lockfile_name = pm.lockfile_name();
+			let lockfile = dir.join(lockfile_name);
+			if !lockfile.is_file() {
+				continue;
+			}
+			let dist_name = format!("{}-{}", name.replace("/", "-"), lockfile_name);
+			let dist = audit_dir.join(dist_name);
+			copy(lockfile, dist)?;
+		}
+
+		// persist the findings in the audit history db, and optionally diff against the previous run
+		let conn = db::open(audit_root_dir)?;
+		db::insert_run(&conn, &package_audits)?;
+
+		if cmd.is_present("diff") {
+			print_diff(&conn)?;
+		}
+
+		// a broken/offline audit invocation is always reported, but only flips the exit code
+		// when the caller opted in via --fail-on-audit-error: otherwise a single unreachable
+		// or private package in a larger monorepo would silently fail every plain `naudit` run
+		if audit_failed {
+			println!("=== FAIL - one or more package audits did not complete successfully");
+			if fail_on_audit_error {
+				exit_code = 1;
+			}
+		}
+
+		// compute the highest severity found and fail the run if it meets the threshold
+		if let Some(fail_on) = fail_on {
+			let highest = package_audits.iter().flat_map(|p| p.advisories.iter()).map(|a| a.severity).max();
+			if let Some(highest) = highest {
+				if highest >= fail_on {
+					println!(
+						"=== FAIL - highest severity found '{}' meets or exceeds threshold '{}'",
+						highest.as_str(),
+						fail_on.as_str()
+					);
+					exit_code = 1;
+				}
+			}
+		}
+	} else if cmd.is_present("diff") {
+		// the history in .audit/audit.db outlives any single run, so --diff can still work
+		// against it even when this invocation skipped auditing via --no-audit
+		if audit_root_dir.is_dir() {
+			let conn = db::open(audit_root_dir)?;
+			print_diff(&conn)?;
+		} else {
+			println!("=== DIFF - no audit history found; run naudit without --no-audit at least once first");
 		}
 	}
 
@@ -133,26 +257,72 @@ fn main() {
 		let tar_name = format!("{}.tar", audit_name);
 		println!("=== create tar file {}", tar_name);
 		let tar_path = audit_root_dir.join(&tar_name);
-		let tar_file = File::create(tar_path.as_path()).unwrap();
+		let tar_file = File::create(tar_path.as_path())?;
 		let mut a = Builder::new(tar_file);
 
-		for (file, name) in list_audit_files(audit_dir).unwrap().iter() {
+		// sort entries and write fixed, filesystem-independent headers so the archive is byte-reproducible
+		let mut audit_files = list_audit_files(audit_dir)?;
+		audit_files.sort_by(|(_, name_a), (_, name_b)| name_a.cmp(name_b));
+
+		for (file, name) in audit_files.iter() {
 			let name = format!("{}/{}", audit_name, name);
-			a.append_file(name, &mut File::open(file).unwrap()).unwrap();
+			let mut file = File::open(file)?;
+			let size = file.metadata()?.len();
+
+			let mut header = tar::Header::new_gnu();
+			header.set_size(size);
+			header.set_mtime(TAR_EPOCH);
+			header.set_uid(0);
+			header.set_gid(0);
+			header.set_mode(0o644);
+			header.set_cksum();
+
+			a.append_data(&mut header, &name, &mut file)?;
 		}
 
 		// create gz
 		let gz_name = format!("{}.gz", &tar_name);
 		println!("=== creating gz file {}", tar_name);
-		let tar_file = File::open(tar_path.as_path()).unwrap();
+		let tar_file = File::open(tar_path.as_path())?;
 		let mut reader = BufReader::new(tar_file);
-		let mut encoder = Encoder::new(Vec::new()).unwrap();
-		io::copy(&mut reader, &mut encoder).unwrap();
-		let encoded_data = encoder.finish().into_result().unwrap();
+		let mut encoder = Encoder::new(Vec::new())?;
+		io::copy(&mut reader, &mut encoder)?;
+		let encoded_data = encoder.finish().into_result()?;
 		let gz_file = audit_root_dir.join(gz_name);
-		let mut gz_file = File::create(gz_file.as_path()).unwrap();
-		gz_file.write_all(&encoded_data).expect("Fail to create gz");
+		let mut gz_file = File::create(gz_file.as_path())?;
+		gz_file.write_all(&encoded_data)?;
+	}
+
+	Ok(exit_code)
+}
+
+/// Loads the two most recent runs from the history db and prints the new/resolved/unchanged sets.
+fn print_diff(conn: &Connection) -> Result<(), MainError> {
+	match db::last_two_runs(conn)? {
+		Some((previous_id, latest_id)) => {
+			let previous = db::findings_for_run(conn, previous_id)?;
+			let current = db::findings_for_run(conn, latest_id)?;
+			let diff = db::diff_runs(&previous, &current);
+
+			println!("=== DIFF - new findings ({})", diff.new_findings.len());
+			for f in diff.new_findings.iter() {
+				println!("  + {} {} ({})", f.package_dir, f.module_name, f.severity);
+			}
+
+			println!("=== DIFF - resolved findings ({})", diff.resolved_findings.len());
+			for f in diff.resolved_findings.iter() {
+				println!("  - {} {} ({})", f.package_dir, f.module_name, f.severity);
+			}
+
+			println!("=== DIFF - unchanged findings ({})", diff.unchanged_findings.len());
+			for f in diff.unchanged_findings.iter() {
+				println!("  = {} {} ({})", f.package_dir, f.module_name, f.severity);
+			}
+		}
+		None => println!("=== DIFF - no previous run to diff against"),
 	}
+
+	Ok(())
 }
 
 // region:    package parser
@@ -170,56 +340,32 @@ fn get_drop_name(root: &Path) -> Result<String, MainError> {
 // endregion: package parser
 
 // region:    cmds
-fn cmd_install(dir: &Path) {
-	let mut proc = Command::new("npm")
-		.current_dir(dir)
-		.arg("install")
-		.arg("--colors")
-		.spawn()
-		.expect("failed to execute process");
-
-	proc.wait().expect("Fail to wap for npm install");
-}
+fn cmd_install(dir: &Path, pm: PackageManager) -> Result<(), MainError> {
+	let mut cmd = Command::new(pm.binary());
+	cmd.current_dir(dir).arg("install");
+	if pm == PackageManager::Npm {
+		cmd.arg("--colors");
+	}
 
-fn cmd_audit(dir: &Path) -> String {
-	let output = Command::new("npm")
-		.current_dir(dir)
-		.arg("audit")
-		.arg("--audit-level=moderate")
-		.stdout(Stdio::piped())
-		.output()
-		.expect("failed to execute process");
-	let mut output = String::from_utf8(output.stdout).unwrap();
-
-	// Note: need to format and filter since the output seems to have special characters
-	// TODO: Probably a simpler way to do this
-	output = output.replace("[90m", "");
-	output = output.replace("[39m", "");
-	// clean each line
-	output = output
-		.lines()
-		.map(|s| {
-			s.replace(|c: char| !c.is_alphanumeric() && !c.is_whitespace(), "")
-				.trim()
-				.to_owned()
-		})
-		.collect::<Vec<String>>()
-		.join("\n");
-	output
+	let mut proc = cmd.spawn()?;
+	proc.wait()?;
+	Ok(())
 }
 
 fn clean_packages(root: &Path) -> Result<(), MainError> {
 	let dirs = list_package_dirs(root)?;
 
-	for (dir, name) in dirs {
+	for (dir, name, detected_pm) in dirs {
 		println!("=== clean {}", name);
 		// delete node_modules/
 		let node_modules_dir = dir.join("node_modules");
 		safer_remove_dir(node_modules_dir.as_path())?;
 
-		// delete package-lock.json
-		let package_lock_file = dir.join("package-lock.json");
-		safer_remove_file(package_lock_file.as_path())?;
+		// delete the lockfile, if any
+		if let Some(detected_pm) = detected_pm {
+			let lockfile = dir.join(detected_pm.lockfile_name());
+			safer_remove_file(lockfile.as_path())?;
+		}
 	}
 
 	Ok(())
@@ -227,6 +373,21 @@ fn clean_packages(root: &Path) -> Result<(), MainError> {
 // endregion: cmds
 
 // region:    list files and dirs
+/// Renders a byte count in human-readable units (B, KiB, MiB).
+fn human_size(bytes: u64) -> String {
+	const KIB: f64 = 1024.0;
+	const MIB: f64 = KIB * 1024.0;
+	let bytes_f = bytes as f64;
+
+	if bytes_f >= MIB {
+		format!("{:.2} MiB", bytes_f / MIB)
+	} else if bytes_f >= KIB {
+		format!("{:.2} KiB", bytes_f / KIB)
+	} else {
+		format!("{} B", bytes)
+	}
+}
+
 fn list_audit_files(root: &Path) -> Result<Vec<(PathBuf, String)>, MainError> {
 	let root = canonicalize(root)?;
 	let root = root.as_path();
@@ -247,7 +408,7 @@ fn list_audit_files(root: &Path) -> Result<Vec<(PathBuf, String)>, MainError> {
 	Ok(v)
 }
 
-fn list_package_dirs(root: &Path) -> Result<Vec<(PathBuf, String)>, MainError> {
+fn list_package_dirs(root: &Path) -> Result<Vec<(PathBuf, String, Option<PackageManager>)>, MainError> {
 	let root = canonicalize(root)?;
 	let root = root.as_path();
 
@@ -259,16 +420,18 @@ fn list_package_dirs(root: &Path) -> Result<Vec<(PathBuf, String)>, MainError> {
 	.into_iter()
 	.filter_map(Result::ok);
 
-	let mut v: Vec<(PathBuf, String)> = walker
+	let mut v: Vec<(PathBuf, String, Option<PackageManager>)> = walker
 		.map(|e| {
 			let dir_path: PathBuf = e.into_path().parent().unwrap().into();
 			let rel = dir_path.strip_prefix(root).unwrap();
 			let name = rel.to_str().unwrap().to_owned();
-			(dir_path, name)
+			let detected_pm = pm::detect_lockfile(&dir_path);
+			(dir_path, name, detected_pm)
 		})
 		.collect();
 
-	v.insert(0, (root.to_path_buf().clone(), "_root_".to_owned()));
+	let root_pm = pm::detect_lockfile(root);
+	v.insert(0, (root.to_path_buf().clone(), "_root_".to_owned(), root_pm));
 	Ok(v)
 }
 // endregion: list files and dirs
@@ -291,11 +454,11 @@ fn safer_remove_dir(path: &Path) -> Result<bool, MainError> {
 	}
 }
 
-/// safer remove file, only allow to remove files with "package-lock"
+/// safer remove file, only allow to remove known lockfiles (package-lock.json, yarn.lock, pnpm-lock.yaml)
 fn safer_remove_file(path: &Path) -> Result<bool, MainError> {
 	let path_str = path.to_str().unwrap();
 	// safety guard
-	if !path_str.contains("package-lock") {
+	if !PackageManager::ALL.iter().any(|pm| path_str.ends_with(pm.lockfile_name())) {
 		return Err(MainError::PathNotSafeToDelete(path_str.to_owned()));
 	}
 
@@ -308,3 +471,26 @@ fn safer_remove_file(path: &Path) -> Result<bool, MainError> {
 	}
 }
 // endregion: safer_remove funtions
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn human_size_formats_bytes_below_kib() {
+		assert_eq!(human_size(0), "0 B");
+		assert_eq!(human_size(1023), "1023 B");
+	}
+
+	#[test]
+	fn human_size_formats_kib_range() {
+		assert_eq!(human_size(1024), "1.00 KiB");
+		assert_eq!(human_size(1536), "1.50 KiB");
+	}
+
+	#[test]
+	fn human_size_formats_mib_range() {
+		assert_eq!(human_size(1024 * 1024), "1.00 MiB");
+		assert_eq!(human_size(5 * 1024 * 1024 + 512 * 1024), "5.50 MiB");
+	}
+}
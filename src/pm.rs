@@ -0,0 +1,49 @@
+use crate::MainError;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The supported JS package managers: each knows its own binary name and lockfile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PackageManager {
+	Npm,
+	Yarn,
+	Pnpm,
+}
+
+impl PackageManager {
+	pub const ALL: [PackageManager; 3] = [PackageManager::Npm, PackageManager::Yarn, PackageManager::Pnpm];
+
+	pub fn binary(&self) -> &'static str {
+		match self {
+			PackageManager::Npm => "npm",
+			PackageManager::Yarn => "yarn",
+			PackageManager::Pnpm => "pnpm",
+		}
+	}
+
+	pub fn lockfile_name(&self) -> &'static str {
+		match self {
+			PackageManager::Npm => "package-lock.json",
+			PackageManager::Yarn => "yarn.lock",
+			PackageManager::Pnpm => "pnpm-lock.yaml",
+		}
+	}
+}
+
+impl FromStr for PackageManager {
+	type Err = MainError;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"npm" => Ok(PackageManager::Npm),
+			"yarn" => Ok(PackageManager::Yarn),
+			"pnpm" => Ok(PackageManager::Pnpm),
+			_ => Err(MainError::InvalidArgument(format!("Unknown package manager '{}'", s))),
+		}
+	}
+}
+
+/// Returns the package manager whose lockfile already exists in `dir`, if any.
+pub fn detect_lockfile(dir: &Path) -> Option<PackageManager> {
+	PackageManager::ALL.iter().copied().find(|pm| dir.join(pm.lockfile_name()).is_file())
+}